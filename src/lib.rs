@@ -0,0 +1,351 @@
+//! Core byte/line/word/character counting logic for `wc`, split out of
+//! the binary so the counters can be reused (and tested) on their own,
+//! independent of CLI argument parsing.
+
+extern crate unicode_width;
+
+use unicode_width::UnicodeWidthChar;
+use std::cmp::max;
+use std::error::Error;
+use std::fmt::Result as FmtResult;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::Error as IOError;
+use std::io::{BufReader, stdin};
+use std::io::{BufRead, Read};
+use std::str::Utf8Error;
+
+const LF: char = '\n';
+const NULL: char = '\0';
+
+// Enum that captures all of the expected error variants we are likely
+// to return.
+#[derive(Debug)]
+pub enum WCError {
+    IO(IOError),
+    Utf8(Utf8Error),
+    EmptyFileName,
+}
+
+// Implement the Display trait so that we can print our errors to the
+// user. Also a pre-requisite for implementing the Error trait.
+impl Display for WCError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match *self {
+            WCError::IO(ref e) => e.fmt(f),
+            WCError::Utf8(ref e) => e.fmt(f),
+            WCError::EmptyFileName => write!(f, "invalid zero-length file name"),
+        }
+    }
+}
+
+// Implement the Error trait so that callers can treat a WCError as
+// they would any other error.
+impl Error for WCError {
+    fn description(&self) -> &str {
+        match *self {
+            WCError::IO(ref e) => e.description(),
+            WCError::Utf8(ref e) => e.description(),
+            WCError::EmptyFileName => "invalid zero-length file name",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            WCError::IO(ref e) => e.cause(),
+            WCError::Utf8(ref e) => e.cause(),
+            WCError::EmptyFileName => None,
+        }
+    }
+}
+
+// Make it possible to wrap an IOError as a variant of our WCError enum.
+impl From<IOError> for WCError {
+    fn from(e: IOError) -> WCError {
+        WCError::IO(e)
+    }
+}
+
+// Make it possible to wrap a Utf8Error as a variant of our WCError enum.
+impl From<Utf8Error> for WCError {
+    fn from(e: Utf8Error) -> WCError {
+        WCError::Utf8(e)
+    }
+}
+
+// Wrapper around Result that specializes the Error variant to be one
+// of our WCErrors.
+pub type WCResult<T> = Result<T, WCError>;
+
+// Selects which counts a caller wants out of `count`/`count_file`,
+// playing the role a bitflags type would here without pulling in a
+// dependency just for five booleans.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CountFlags {
+    pub bytes: bool,
+    pub chars: bool,
+    pub lines: bool,
+    pub words: bool,
+    pub max_line_length: bool,
+}
+
+impl CountFlags {
+    pub fn none() -> CountFlags {
+        CountFlags::default()
+    }
+
+    // Whether anything we've been asked for requires decoding the
+    // bytes as text rather than just tallying them.
+    fn needs_decoding(&self) -> bool {
+        self.lines || self.words || self.chars || self.max_line_length
+    }
+}
+
+// Define the struct which will wrap all of our counts. Derive the
+// Debug trait so we can print it out using the "{:?}" pattern for
+// debugging purposes.
+#[derive(Debug, Clone, Copy)]
+pub struct Counts {
+    pub bytes: usize,
+    pub chars: usize,
+    pub lines: usize,
+    pub words: usize,
+    pub max_line_length: usize,
+}
+
+impl Counts {
+    pub fn new() -> Counts {
+        Counts {
+            bytes: 0,
+            chars: 0,
+            lines: 0,
+            words: 0,
+            max_line_length: 0,
+        }
+    }
+}
+
+// max_line_length doesn't behave like a sum, so this isn't a
+// field-by-field addition, but it lets callers fold a Vec<Counts>
+// into a "total" with the operator they'd reach for first.
+impl ::std::ops::Add for Counts {
+    type Output = Counts;
+
+    fn add(self, other: Counts) -> Counts {
+        Counts {
+            bytes: self.bytes + other.bytes,
+            chars: self.chars + other.chars,
+            lines: self.lines + other.lines,
+            words: self.words + other.words,
+            max_line_length: max(self.max_line_length, other.max_line_length),
+        }
+    }
+}
+
+// Return the named file, but opened, and in a result that's usable as
+// a buffered reader. In the case of a filename "-", return an
+// appropriately wrapped Stdin. Doing this allows us to treat regular
+// files and stdin equivalently.
+pub fn open_file(filename: &str) -> WCResult<Box<BufRead>> {
+    match filename {
+        "-" => Ok(Box::new(BufReader::new(stdin()))),
+        _ => Ok(Box::new(BufReader::new(try!(File::open(filename))))),
+    }
+}
+
+// When only the byte count is wanted, a regular file's size is already
+// known to the kernel via fstat(2), so we can report it directly
+// instead of reading (and discarding) every byte of the file. Returns
+// None for anything that isn't a plain regular file (pipes, character
+// devices, and similarly stat-unreliable descriptors such as /proc
+// entries that report a size of 0), so the caller can fall back to the
+// regular read loop.
+#[cfg(unix)]
+fn regular_file_size(file: &File) -> Option<usize> {
+    match file.metadata() {
+        Ok(meta) => {
+            if meta.is_file() && meta.len() > 0 {
+                Some(meta.len() as usize)
+            } else {
+                None
+            }
+        },
+        Err(_) => None,
+    }
+}
+
+#[cfg(not(unix))]
+fn regular_file_size(_file: &File) -> Option<usize> {
+    None
+}
+
+// Count the '\n' bytes in a word of the buffer using the classic SWAR
+// (SIMD-within-a-register) trick for locating a target byte: XOR each
+// byte against the target so matching bytes become zero, then add 0x7f
+// to the low seven bits of each byte and OR in the byte itself, which
+// sets a byte's high bit exactly when that byte is nonzero (the sum
+// never carries into the next byte). Inverting that leaves one high bit
+// per zeroed byte, so count_ones() is the number of matches in the word.
+fn count_lf_word(word: u64) -> usize {
+    const LO7: u64 = 0x7f7f7f7f7f7f7f7f;
+    const LF_REPEATED: u64 = 0x0a0a0a0a0a0a0a0a;
+    let x = word ^ LF_REPEATED;
+    (!(((x & LO7) + LO7) | x | LO7)).count_ones() as usize
+}
+
+fn word_from_bytes(b: &[u8]) -> u64 {
+    (b[0] as u64) | (b[1] as u64) << 8 | (b[2] as u64) << 16 | (b[3] as u64) << 24 |
+        (b[4] as u64) << 32 | (b[5] as u64) << 40 | (b[6] as u64) << 48 | (b[7] as u64) << 56
+}
+
+// Count '\n' bytes in a buffer by processing it 8 bytes (one machine
+// word) at a time rather than one byte at a time, falling back to a
+// plain byte scan for the final, less-than-a-word remainder.
+fn count_newlines(buf: &[u8]) -> usize {
+    let mut count = 0;
+    let mut chunks = buf.chunks(8);
+    loop {
+        match chunks.next() {
+            Some(chunk) if chunk.len() == 8 => count += count_lf_word(word_from_bytes(chunk)),
+            Some(tail) => count += tail.iter().filter(|&&b| b == LF as u8).count(),
+            None => break,
+        }
+    }
+    count
+}
+
+// Fast path for `-l`/`-c` (and their combination): read raw bytes into
+// a reusable buffer and tally newlines and byte counts directly,
+// without ever decoding UTF-8 one line at a time via read_until.
+fn count_lines_and_bytes<R: Read>(mut reader: R, count_lines: bool) -> Counted {
+    let mut counts = Counts::new();
+    let mut buf = [0u8; 65536];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(n) => n,
+            Err(e) => return Counted { counts: counts, error: Some(WCError::from(e)) },
+        };
+        if n == 0 {
+            break;
+        }
+        counts.bytes += n;
+        if count_lines {
+            counts.lines += count_newlines(&buf[..n]);
+        }
+    }
+    Counted { counts: counts, error: None }
+}
+
+// Compute the display width of a line in terminal columns, the way
+// GNU wc's -L does: a tab advances to the next multiple of 8, control
+// characters and zero-width combining marks contribute nothing, wide
+// (e.g. East Asian fullwidth) characters count as 2, and everything
+// else counts as 1. The trailing newline, if any, isn't counted.
+fn line_display_width(line: &str) -> usize {
+    let mut column = 0;
+    for c in line.chars() {
+        if c == LF {
+            break;
+        } else if c == '\t' {
+            column += 8 - (column % 8);
+        } else {
+            column += UnicodeWidthChar::width(c).unwrap_or(0);
+        }
+    }
+    column
+}
+
+// General path: decode each line and tally whichever of
+// lines/words/chars/max_line_length were asked for.
+fn count_decoded<R: BufRead>(mut reader: R, which: CountFlags) -> Counted {
+    let mut counts = Counts::new();
+    let mut lbuf = Vec::new();
+    loop {
+        let size = match reader.read_until(LF as u8, &mut lbuf) {
+            Ok(size) => size,
+            Err(e) => return Counted { counts: counts, error: Some(WCError::from(e)) },
+        };
+        counts.bytes += size;
+        if size == 0 {
+            break;
+        }
+        if which.needs_decoding() {
+            // Decode losslessly where possible, but don't bail out on
+            // non-UTF-8 input: invalid sequences are replaced with
+            // U+FFFD and decoding resumes, matching GNU wc's
+            // tolerance of binary/Latin-1 files.
+            let line = String::from_utf8_lossy(&lbuf);
+
+            let size = if which.chars {
+                line.chars().count()
+            } else { 0 };
+
+            let last = if which.lines {
+                line.chars().last().unwrap_or(NULL)
+            } else { NULL };
+
+            if last == LF {
+                counts.lines += 1;
+            }
+
+            if which.max_line_length {
+                counts.max_line_length = max(counts.max_line_length, line_display_width(&line));
+            }
+
+            counts.chars += size;
+
+            if which.words {
+                let mut words: Vec<&str> = line
+                    .split(|c: char| c.is_whitespace())
+                    .collect();
+                words.retain(|s: &&str| s.len() > 0);
+                counts.words += words.len();
+            }
+        }
+        lbuf.clear()
+    }
+    Counted { counts: counts, error: None }
+}
+
+// The result of a count that got far enough to open its input: always
+// carries whatever was tallied before a failure, if any, so a read
+// error partway through a large file doesn't throw away the work
+// already done. `error` is set when the read loop had to stop early;
+// `counts` still reflects everything counted up to that point.
+#[derive(Debug)]
+pub struct Counted {
+    pub counts: Counts,
+    pub error: Option<WCError>,
+}
+
+// Count exactly the metrics requested in `which` from an already-open
+// reader. Callers that have a filename (and so can benefit from the
+// fstat fast path below) should prefer `count_file` instead.
+pub fn count<R: BufRead>(reader: R, which: CountFlags) -> Counted {
+    if (which.lines || which.bytes) && !which.words && !which.chars && !which.max_line_length {
+        return count_lines_and_bytes(reader, which.lines);
+    }
+    count_decoded(reader, which)
+}
+
+// Count exactly the metrics requested in `which` from the named file
+// (or stdin, for "-"), taking the fastest path available: an fstat(2)
+// size lookup when only the byte count is wanted, a raw SIMD newline
+// scan when only lines/bytes are wanted, and a full decode otherwise.
+//
+// The outer WCResult only reports failure to even open the input;
+// once reading has begun, any error is carried inside the returned
+// Counted alongside the partial counts instead, per `count`.
+pub fn count_file(filename: &str, which: CountFlags) -> WCResult<Counted> {
+    if filename != "-" && which.bytes &&
+        !(which.lines || which.words || which.chars || which.max_line_length) {
+            let file = try!(File::open(filename));
+            if let Some(bytes) = regular_file_size(&file) {
+                let mut counts = Counts::new();
+                counts.bytes = bytes;
+                return Ok(Counted { counts: counts, error: None });
+            }
+            return Ok(count(BufReader::new(file), which));
+        }
+    Ok(count(try!(open_file(filename)), which))
+}
@@ -1,20 +1,13 @@
 extern crate rustc_serialize;
 extern crate docopt;
+extern crate wc;
 
 use docopt::Docopt;
-use std::cmp::max;
-use std::error::Error;
-use std::fmt::Result as FmtResult;
-use std::fmt::{Display, Formatter};
-use std::fs::File;
-use std::io::Error as IOError;
-use std::io::{BufReader, stderr, stdin};
-use std::io::{Write, BufRead, Read};
+use std::io::{stderr, BufRead, Write};
 use std::process::exit;
-use std::str::{Utf8Error, from_utf8};
+use std::str::from_utf8;
+use wc::{count_file, open_file, Counted, CountFlags, Counts, WCError, WCResult};
 
-const LF: char = '\n';
-const NULL: char = '\0';
 const VERSION: &'static str = "0.0.1";
 const USAGE: &'static str = "
 Usage: wc [options] FILE...
@@ -76,95 +69,34 @@ macro_rules! println_stderr(
     )
 );
 
-// Enum that captures all of the expected error variants we are likely
-// to return.
-#[derive(Debug)]
-enum WCError {
-    IO(IOError),
-    Utf8(Utf8Error),
-}
-
-// Implement the Display trait so that we can print our errors to the
-// user. Also a pre-requisite for implementing the Error trait.
-impl Display for WCError {
-    fn fmt(&self, f: &mut Formatter) -> FmtResult {
-        match *self {
-            WCError::IO(ref e) => e.fmt(f),
-            WCError::Utf8(ref e) => e.fmt(f),
-        }
-    }
-}
-
-// Implement the Error trait so that callers can treat a WCError as
-// they would any other error.
-impl std::error::Error for WCError {
-    fn description(&self) -> &str {
-        match *self {
-            WCError::IO(ref e) => e.description(),
-            WCError::Utf8(ref e) => e.description()
-        }
-    }
-
-    fn cause(&self) -> Option<&std::error::Error> {
-        match *self {
-            WCError::IO(ref e) => e.cause(),
-            WCError::Utf8(ref e) => e.cause()
-        }
-    }
-}
-
-
-// Make it possible to wrap an IOError as a variant of our WCError enum.
-impl From<IOError> for WCError {
-    fn from(e: IOError) -> WCError {
-        WCError::IO(e)
-    }
-}
-
-// Make it possible to wrap a Utf8Error as a variant of our WCError enum.
-impl From<Utf8Error> for WCError {
-    fn from(e: Utf8Error) -> WCError {
-        WCError::Utf8(e)
+// Translate the docopt-parsed flags into the library's selector type.
+fn flags_from_args(args: &Args) -> CountFlags {
+    CountFlags {
+        bytes: args.flag_bytes,
+        chars: args.flag_chars,
+        lines: args.flag_lines,
+        words: args.flag_words,
+        max_line_length: args.flag_max_line_length,
     }
 }
 
-// Define the struct which will wrap all of our counts. Derive the
-// Debug trait so we can print it out using the "{:?}" pattern for
-// debugging purposes.
-#[derive(Debug)]
-struct FileInfo{
-    bytes: usize,
-    chars: usize,
-    lines: usize,
-    words: usize,
-    max_line_length: usize,
+// Thin wrapper over the library's count_file, passing along exactly
+// the metrics the CLI flags asked for.
+fn process_file(filename: &str, args: &Args) -> WCResult<Counted> {
+    count_file(filename, flags_from_args(args))
 }
 
-// Implement a convenience constructor that will return a zeroed
-// FileInfo struct. I had considered implementing the Zero and Add
-// traits and using sum to acquire our "total" instead of a fold, but
-// the behavior of max_line_length just doesn't look like add to me,
-// and it may be more confusing that it's worth.
-impl FileInfo {
-    fn new() -> FileInfo {
-        FileInfo{bytes: 0,
-                 chars: 0,
-                 lines: 0,
-                 words: 0,
-                 max_line_length: 0,
-        }
-    }
-}
-
-// Wrapper around Result that specializes the Error variant to be one
-// of our WCErrors.
-type WCResult<T> = Result<T, WCError>;
-
-// Print the result of attempting to process a file.
-fn display(args: &Args, filename: &str, result: &WCResult<FileInfo>,
+// Print the result of attempting to process a file. A result that
+// couldn't even be opened prints only the error; one that was opened
+// and read (even partially, if a read error cut it short) prints
+// whatever was counted, followed by the error if there was one. Only
+// the latter case ever contributes to the "total" line, via the
+// Counts the caller pulls out of Ok results.
+fn display(args: &Args, filename: &str, result: &WCResult<Counted>,
            field_size: usize) -> bool {
     match *result {
-        Ok(ref r) => {
+        Ok(ref counted) => {
+            let r = &counted.counts;
             if args.flag_lines {
                 print!("{:1$} ", r.lines, field_size);
             }
@@ -181,7 +113,13 @@ fn display(args: &Args, filename: &str, result: &WCResult<FileInfo>,
                 print!("{:1$} ", r.max_line_length, field_size);
             }
             println!("{}", filename);
-            true
+            match counted.error {
+                Some(ref e) => {
+                    println_stderr!("wc: {}: {}", filename, e);
+                    false
+                },
+                None => true,
+            }
         },
         Err(ref e) => {
             println_stderr!("wc: {}: {}", filename, e);
@@ -190,92 +128,41 @@ fn display(args: &Args, filename: &str, result: &WCResult<FileInfo>,
     }
 }
 
-
-// Return the named file, but opened, and in a result that's usable as
-// a buffered reader. In the case of a filename "-", return an
-// appropriately wrapped Stdin. Doing this allows us to treat regular
-// files and stdin equivalently.
-fn open_file(filename: &str) -> WCResult<Box<BufRead>> {
-    match filename {
-        "-" => Ok(Box::new(BufReader::new(stdin()))),
-        _ => Ok(Box::new(BufReader::new(try!(File::open(filename))))),
-    }
+// Yields one NUL-terminated name at a time from a --files0-from
+// source, rather than reading the whole list into memory up front.
+// This lets the caller start processing and printing files as their
+// names are produced instead of waiting for the entire list to parse.
+struct Files0From {
+    reader: Box<BufRead>,
 }
 
-fn process_file(filename: &str, args: &Args) -> WCResult<FileInfo> {
-    let mut file = try!(open_file(filename));
-    let mut info = FileInfo::new();
-    let mut lbuf = Vec::new();
-    loop {
-        let size = try!(file.read_until(LF as u8, &mut lbuf));
-        info.bytes += size;
-        if size == 0 {
-            break;
-        }
-        // If this if statement wasn't here, we would still need to
-        // create a scope because we're going to borrow lbuf and the
-        // borrow must end before we can clear it.
-        if args.flag_lines || args.flag_words || args.flag_chars ||
-            args.flag_max_line_length {
-                // TODO: Handle files which are not utf8-encoded. Right
-                // now we get an error here.
-                let line = try!(from_utf8(&lbuf));
-
-                let size = if args.flag_chars || args.flag_max_line_length {
-                    line.chars().count()
-                } else { 0 };
-
-                let last = if args.flag_lines || args.flag_max_line_length {
-                    line.chars().last().unwrap_or(NULL)
-                } else { NULL };
-
-                if last == LF {
-                    info.lines += 1;
-                }
-
-                if args.flag_max_line_length {
-                    info.max_line_length = if last == LF {
-                        max(info.max_line_length, size - 1)
-                    } else {
-                        max(info.max_line_length, size)
-                    };
-                }
-
-                info.chars += size;
-
-                if args.flag_words {
-                    let mut words: Vec<&str> = line
-                        .split(|c: char| c.is_whitespace())
-                        .collect();
-                    words.retain(|s: &&str| s.len() > 0);
-                    info.words += words.len();
-                }
-            }
-        lbuf.clear()
+impl Files0From {
+    fn new(filename: &str) -> WCResult<Files0From> {
+        Ok(Files0From { reader: try!(open_file(filename)) })
     }
-    Ok(info)
 }
 
-// Open the file (possibly - for stdin) and return an array of strings
-// reflecting the contents of the file, split on null characters.
-fn process_files0_from(filename: &str) -> WCResult<Vec<String>> {
-    let mut file = try!(open_file(filename));
-    let mut result = Vec::new();
-    let mut lbuf = Vec::new();
-    loop {
-        let size = try!(file.read_until(NULL as u8, &mut lbuf));
+impl Iterator for Files0From {
+    type Item = WCResult<String>;
+
+    fn next(&mut self) -> Option<WCResult<String>> {
+        let mut lbuf = Vec::new();
+        let size = match self.reader.read_until(0u8, &mut lbuf) {
+            Ok(size) => size,
+            Err(e) => return Some(Err(WCError::from(e))),
+        };
         if size == 0 {
-            break;
+            return None;
         }
-        // Create a scope because we're going to borrow lbuf and
-        // the borrow must end before we can clear it.
-        {
-            let line = try!(from_utf8(&lbuf));
-            result.push(line.trim_right_matches(NULL).to_string());
+        let name = match from_utf8(&lbuf) {
+            Ok(s) => s.trim_right_matches('\0').to_string(),
+            Err(e) => return Some(Err(WCError::from(e))),
+        };
+        if name.is_empty() {
+            return Some(Err(WCError::EmptyFileName));
         }
-        lbuf.clear()
+        Some(Ok(name))
     }
-    Ok(result)
 }
 
 fn main() {
@@ -303,55 +190,33 @@ fn main() {
         args.flag_bytes = true;
     }
 
-    // Determine what "files" to process
-    let mut files: Vec<String> = Vec::new();
+    // --files0-from streams its names, so it's handled separately from
+    // the plain FILE-argument case below.
     if args.flag_files0_from.len() != 0 {
-        match process_files0_from(args.flag_files0_from.as_ref()) {
-            Ok(parts) => files.extend(parts),
-            Err(e) => {
-                println_stderr!("wc: error reading {}: {}",
-                                args.flag_files0_from, e);
-                exit(1);
-            },
-        };
-        if &args.flag_files0_from == "-" && files.contains(&"-".to_string()) {
-            print_stderr!("wc: when reading file names from stdin, no file ");
-            println_stderr!("name of '-' allowed");
+        if !run_files0_from(&args) {
             exit(1);
         }
-    } else {
-        files.extend(args.arg_FILE.clone());
-    };
+        return;
+    }
+
+    let files = args.arg_FILE.clone();
 
     // Process all of our files
-    let results: Vec<WCResult<FileInfo>> = files.iter()
+    let results: Vec<WCResult<Counted>> = files.iter()
         .map(|f| process_file(f, &args))
         .collect();
 
-    // Fold over the FileInfo results which are of the Ok variant to
-    // compute the "total".
-    let total: FileInfo = results.iter()
-        .filter(|r| r.as_ref().is_ok())
-        .map(|r| r.as_ref().unwrap())
-        .fold(FileInfo::new(),
-              |acc, item|
-              FileInfo{
-                  bytes: acc.bytes + item.bytes,
-                  chars: acc.chars + item.chars,
-                  lines: acc.lines + item.lines,
-                  words: acc.words + item.words,
-                  max_line_length: max(acc.max_line_length,
-                                       item.max_line_length),
-              });
+    // Fold over the Ok results to compute the "total", including
+    // partial counts from any file whose read errored part way
+    // through.
+    let total: Counts = results.iter()
+        .filter_map(|r| r.as_ref().ok())
+        .fold(Counts::new(), |acc, item| acc + item.counts);
 
     // This is used for formatting. The number in the byte count will
     // be the largest, and so will be the widest string, so it's
     // suitable for a field width.
-    let field_size = if args.flag_files0_from == "-" {
-        0
-    } else {
-        total.bytes.to_string().len()
-    };
+    let field_size = total.bytes.to_string().len();
 
     // For determining eventual exit code
     let mut ok = true;
@@ -362,9 +227,61 @@ fn main() {
         ok &= display(&args, filename, result, field_size)
     }
     if results.len() > 1 {
-        display(&args, "total", &Ok(total), field_size);
+        display(&args, "total", &Ok(Counted { counts: total, error: None }), field_size);
     }
     if !ok {
         exit(1);
     }
 }
+
+// Stream names out of --files0-from, processing and printing each
+// file's counts as its name is produced instead of buffering the
+// whole name list (and every file's output) until the end. Returns
+// whether every file was processed without error, mirroring the `ok`
+// bookkeeping the FILE-argument path does inline in main.
+fn run_files0_from(args: &Args) -> bool {
+    let reject_dash = args.flag_files0_from == "-";
+    let names = match Files0From::new(args.flag_files0_from.as_ref()) {
+        Ok(names) => names,
+        Err(e) => {
+            println_stderr!("wc: error reading {}: {}", args.flag_files0_from, e);
+            exit(1);
+        },
+    };
+
+    // Field width can't be derived from a grand total we haven't seen
+    // yet, so (as with --files0-from=-) counts aren't column-aligned.
+    let field_size = 0;
+    let mut total = Counts::new();
+    let mut count = 0;
+    let mut ok = true;
+
+    for name in names {
+        let filename = match name {
+            Ok(filename) => filename,
+            Err(e) => {
+                println_stderr!("wc: {}", e);
+                ok = false;
+                continue;
+            },
+        };
+        if reject_dash && filename == "-" {
+            print_stderr!("wc: when reading file names from stdin, no file ");
+            println_stderr!("name of '-' allowed");
+            exit(1);
+        }
+
+        count += 1;
+        let result = process_file(filename.as_ref(), args);
+        if let Ok(ref counted) = result {
+            total = total + counted.counts;
+        }
+        ok &= display(args, filename.as_ref(), &result, field_size);
+    }
+
+    if count > 1 {
+        display(args, "total", &Ok(Counted { counts: total, error: None }), field_size);
+    }
+
+    ok
+}